@@ -1,4 +1,9 @@
 //! Utilities for retrying falliable, asynchronous operations.
+//!
+//! Delays between attempts can follow either exponential backoff or
+//! decorrelated jitter backoff; see [`BackoffStrategy`]. Per-attempt and
+//! overall deadlines are available via `attempt_timeout` and
+//! `total_timeout`.
 
 use std::fmt::Debug;
 use std::time::Duration;
@@ -26,8 +31,70 @@ pub struct Retry {
     /// Exponential factor to increase the delay by on each attempt.
     pub delay_factor: f64,
 
-    /// If true, the delay will be selected randomly from the range [delay/2, delay).
-    pub enable_jitter: bool,
+    /// The maximum delay between attempts, regardless of how large
+    /// `delay_factor` would otherwise grow it.
+    ///
+    /// The cap applies to the delay as grown by `delay_factor`, before
+    /// jitter, so a jittered sleep can exceed it by up to
+    /// `randomization_factor`. `base_delay` itself is not capped. Only used
+    /// by [`BackoffStrategy::Exponential`].
+    pub max_delay: Duration,
+
+    /// Spreads the realized delay randomly within
+    /// `delay * [1 - randomization_factor, 1 + randomization_factor]`.
+    ///
+    /// A factor of `0.0` (the default) disables jitter entirely. Only used
+    /// by [`BackoffStrategy::Exponential`].
+    pub randomization_factor: f64,
+
+    /// The backoff strategy used to compute the delay between attempts.
+    pub backoff: BackoffStrategy,
+
+    /// If set, each individual attempt is aborted and treated as a
+    /// retryable failure if it doesn't complete within this duration.
+    pub attempt_timeout: Option<Duration>,
+
+    /// If set, the retry loop stops, returning the last error seen, when the
+    /// wall-clock time since the first attempt plus the upcoming sleep would
+    /// reach this duration. The check is made before each sleep; an attempt
+    /// already in progress is not interrupted.
+    pub total_timeout: Option<Duration>,
+}
+
+/// The randomization factor applied by [`Retry::jitter`], spreading delays
+/// by ±25% around their nominal value.
+const DEFAULT_RANDOMIZATION_FACTOR: f64 = 0.25;
+
+/// The strategy used to compute the delay between retry attempts.
+#[derive(Copy, Clone, Debug)]
+pub enum BackoffStrategy {
+    /// Multiplicative backoff: the delay grows by `delay_factor` on each
+    /// attempt, capped at `max_delay` and optionally spread by
+    /// `randomization_factor`.
+    Exponential,
+
+    /// "Decorrelated jitter" backoff, which disperses concurrent clients
+    /// better than multiplicative backoff (see the AWS Architecture Blog
+    /// post "Exponential Backoff and Jitter"). Each delay is drawn uniformly
+    /// from `[base_delay, 3 * previous_delay]` and capped at `cap`, so the
+    /// delay is always at least `base_delay` and never exceeds `cap`.
+    Decorrelated {
+        /// The maximum delay to sleep between attempts.
+        cap: Duration,
+    },
+}
+
+/// The error returned by [`Retry::run`] and its variants: either the
+/// wrapped function's own error, or a timeout waiting for a single attempt
+/// to complete.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RetryError<E> {
+    /// The wrapped function returned this error.
+    Inner(E),
+
+    /// The last attempt made did not complete within `attempt_timeout`.
+    /// Errors returned by earlier attempts are not reported here.
+    Timeout,
 }
 
 impl Retry {
@@ -38,7 +105,11 @@ impl Retry {
             attempts: 3,
             base_delay: Duration::ZERO,
             delay_factor: 1.0,
-            enable_jitter: false,
+            max_delay: Duration::MAX,
+            randomization_factor: 0.0,
+            backoff: BackoffStrategy::Exponential,
+            attempt_timeout: None,
+            total_timeout: None,
         }
     }
 
@@ -60,48 +131,231 @@ impl Retry {
         self
     }
 
-    /// Enable jitter.
+    /// Set the maximum delay to sleep between attempts, capping the
+    /// exponential growth driven by `delay_factor`.
+    pub const fn max_delay(mut self, max_delay: Duration) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Enable or disable jitter, using the default ±25% randomization factor.
     pub const fn jitter(mut self, enabled: bool) -> Self {
-        self.enable_jitter = enabled;
+        self.randomization_factor = if enabled {
+            DEFAULT_RANDOMIZATION_FACTOR
+        } else {
+            0.0
+        };
+        self
+    }
+
+    /// Set the randomization factor used to jitter delays, spreading the
+    /// realized delay within `delay * [1 - factor, 1 + factor]`.
+    pub const fn randomization_factor(mut self, randomization_factor: f64) -> Self {
+        self.randomization_factor = randomization_factor;
+        self
+    }
+
+    /// Set the backoff strategy used to compute the delay between attempts.
+    pub const fn backoff(mut self, backoff: BackoffStrategy) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set a timeout for each individual attempt, after which it is treated
+    /// as a retryable failure.
+    pub const fn attempt_timeout(mut self, attempt_timeout: Duration) -> Self {
+        self.attempt_timeout = Some(attempt_timeout);
+        self
+    }
+
+    /// Set an overall deadline for the whole retry loop, including sleeps
+    /// between attempts.
+    pub const fn total_timeout(mut self, total_timeout: Duration) -> Self {
+        self.total_timeout = Some(total_timeout);
         self
     }
 
     fn apply_jitter(&self, delay: Duration) -> Duration {
-        if self.enable_jitter {
-            // [0.5, 1.0)
-            delay.mul_f64(0.5 + fastrand::f64() / 2.0)
+        if self.randomization_factor > 0.0 {
+            let factor = 1.0 + self.randomization_factor * (fastrand::f64() * 2.0 - 1.0);
+            delay.mul_f64(factor.max(0.0))
         } else {
             delay
         }
     }
 
+    /// Computes the delay to sleep before the next attempt under the
+    /// configured backoff strategy, advancing `delay` in place.
+    fn next_delay(&self, delay: &mut Duration) -> Duration {
+        match self.backoff {
+            BackoffStrategy::Exponential => {
+                let sleep_for = self.apply_jitter(*delay);
+                *delay = delay.mul_f64(self.delay_factor).min(self.max_delay);
+                sleep_for
+            }
+            BackoffStrategy::Decorrelated { cap } => {
+                let span = delay.mul_f64(3.0).saturating_sub(self.base_delay);
+                *delay = (self.base_delay + span.mul_f64(fastrand::f64())).min(cap);
+                *delay
+            }
+        }
+    }
+
+    /// Returns true if sleeping for `upcoming_sleep` would reach or exceed
+    /// `total_timeout`, measured from `clock_start`.
+    fn budget_exceeded(&self, clock_start: time::Instant, upcoming_sleep: Duration) -> bool {
+        self.total_timeout
+            .is_some_and(|total| clock_start.elapsed() + upcoming_sleep >= total)
+    }
+
     /// Run a falliable asynchronous function using this retry configuration.
     ///
     /// Panics if the number of attempts is set to `0`, or the base delay is
     /// incorrectly set to a negative duration.
     pub async fn run<T, E: Debug>(
+        self,
+        func: impl AsyncFnMut() -> Result<T, E>,
+    ) -> Result<T, RetryError<E>> {
+        self.run_if(func, |_| true).await
+    }
+
+    /// Run a falliable asynchronous function, retrying only on errors for
+    /// which `should_retry` returns `true`.
+    ///
+    /// Errors rejected by `should_retry` are returned immediately, without
+    /// sleeping or consuming a further attempt. This is useful to fail fast
+    /// on permanent errors (e.g. a 4xx-style response) while still retrying
+    /// transient ones (e.g. a connection reset).
+    ///
+    /// Panics if the number of attempts is set to `0`, or the base delay is
+    /// incorrectly set to a negative duration.
+    pub async fn run_if<T, E: Debug>(
+        self,
+        func: impl AsyncFnMut() -> Result<T, E>,
+        should_retry: impl FnMut(&E) -> bool,
+    ) -> Result<T, RetryError<E>> {
+        let name = self.name;
+        self.run_with(func, should_retry, move |_attempt, err, _delay| match err {
+            Some(err) => warn!(?err, "failed retryable operation {}, retrying", name),
+            None => warn!("operation {} timed out, retrying", name),
+        })
+        .await
+    }
+
+    /// Run a falliable asynchronous function, retrying only on errors for
+    /// which `should_retry` returns `true`, and invoking `on_retry` with the
+    /// zero-based attempt index, the error, and the delay about to be slept
+    /// before each retry.
+    ///
+    /// This replaces the default `warn!` logging done by [`Retry::run_if`],
+    /// letting callers emit custom tracing spans, increment metrics, or
+    /// downgrade the log level instead. `on_retry` is also invoked when an
+    /// attempt is retried because `attempt_timeout` elapsed, with `None` in
+    /// place of an error, so timeout-driven retries are observable too.
+    ///
+    /// Panics if the number of attempts is set to `0`, or the base delay is
+    /// incorrectly set to a negative duration.
+    pub async fn run_with<T, E: Debug>(
         self,
         mut func: impl AsyncFnMut() -> Result<T, E>,
-    ) -> Result<T, E> {
+        mut should_retry: impl FnMut(&E) -> bool,
+        mut on_retry: impl FnMut(u32, Option<&E>, Duration),
+    ) -> Result<T, RetryError<E>> {
         assert!(self.attempts > 0, "attempts must be greater than 0");
         assert!(
             self.base_delay >= Duration::ZERO && self.delay_factor >= 0.0,
             "retry delay cannot be negative"
         );
+        let clock_start = time::Instant::now();
         let mut delay = self.base_delay;
         for i in 0..self.attempts {
-            match func().await {
-                Ok(value) => return Ok(value),
-                Err(err) if i == self.attempts - 1 => return Err(err),
-                Err(err) => {
-                    warn!(?err, "failed retryable operation {}, retrying", self.name);
-                    time::sleep(self.apply_jitter(delay)).await;
-                    delay = delay.mul_f64(self.delay_factor);
+            let outcome = match self.attempt_timeout {
+                Some(attempt_timeout) => time::timeout(attempt_timeout, func()).await.ok(),
+                None => Some(func().await),
+            };
+            match outcome {
+                Some(Ok(value)) => return Ok(value),
+                Some(Err(err)) => {
+                    if i == self.attempts - 1 || !should_retry(&err) {
+                        return Err(RetryError::Inner(err));
+                    }
+                    let sleep_for = self.next_delay(&mut delay);
+                    if self.budget_exceeded(clock_start, sleep_for) {
+                        return Err(RetryError::Inner(err));
+                    }
+                    on_retry(i, Some(&err), sleep_for);
+                    time::sleep(sleep_for).await;
+                }
+                None => {
+                    if i == self.attempts - 1 {
+                        return Err(RetryError::Timeout);
+                    }
+                    let sleep_for = self.next_delay(&mut delay);
+                    if self.budget_exceeded(clock_start, sleep_for) {
+                        return Err(RetryError::Timeout);
+                    }
+                    on_retry(i, None, sleep_for);
+                    time::sleep(sleep_for).await;
                 }
             }
         }
         unreachable!();
     }
+
+    /// Run a falliable asynchronous function, returning the number of
+    /// attempts made and every intermediate error alongside the final
+    /// result, instead of discarding that history like [`Retry::run`] does.
+    ///
+    /// `errors` holds a clone of every error seen on a retried attempt, in
+    /// order; the final error (if any) is only present in `result`.
+    ///
+    /// Panics if the number of attempts is set to `0`, or the base delay is
+    /// incorrectly set to a negative duration.
+    pub async fn run_detailed<T, E: Debug + Clone>(
+        self,
+        mut func: impl AsyncFnMut() -> Result<T, E>,
+    ) -> RetryOutcome<T, E> {
+        let name = self.name;
+        let mut attempts = 0;
+        let mut errors = Vec::new();
+        let result = self
+            .run_with(
+                async || {
+                    attempts += 1;
+                    func().await
+                },
+                |_| true,
+                |_attempt, err: Option<&E>, _delay| match err {
+                    Some(err) => {
+                        warn!(?err, "failed retryable operation {}, retrying", name);
+                        errors.push(err.clone());
+                    }
+                    None => warn!("operation {} timed out, retrying", name),
+                },
+            )
+            .await;
+        RetryOutcome {
+            result,
+            attempts,
+            errors,
+        }
+    }
+}
+
+/// The outcome of a finished retry loop, as returned by
+/// [`Retry::run_detailed`].
+#[derive(Debug)]
+pub struct RetryOutcome<T, E> {
+    /// The final result: `Ok` if some attempt succeeded, or the last `Err`
+    /// otherwise.
+    pub result: Result<T, RetryError<E>>,
+
+    /// The number of attempts made.
+    pub attempts: u32,
+
+    /// Every error seen on a retried attempt, in order, not including the
+    /// final error already present in `result`.
+    pub errors: Vec<E>,
 }
 
 #[cfg(test)]
@@ -110,7 +364,7 @@ mod tests {
 
     use tokio::time::Instant;
 
-    use super::Retry;
+    use super::{BackoffStrategy, Retry, RetryError};
 
     #[tokio::test]
     #[should_panic]
@@ -170,12 +424,86 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn delayed_retry_with_max_delay() {
+        let start = Instant::now();
+
+        let mut count = 0;
+        // Without a cap would retry at 0s, 1s, 3s, 7s, 15s; capped at 2s the
+        // delay plateaus after the first retry: 0s, 1s, 3s, 5s, 7s.
+        let task = Retry::new("test")
+            .attempts(5)
+            .base_delay(Duration::from_secs(1))
+            .delay_factor(2.0)
+            .max_delay(Duration::from_secs(2))
+            .run(async || {
+                count += 1;
+                println!("elapsed = {:?}", start.elapsed());
+                if start.elapsed() < Duration::from_secs(7) {
+                    Err::<(), ()>(())
+                } else {
+                    Ok(())
+                }
+            });
+        let result = task.await;
+        assert_eq!(count, 5);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn decorrelated_backoff_stays_within_bounds() {
+        let start = Instant::now();
+        let base_delay = Duration::from_secs(1);
+        let cap = Duration::from_secs(10);
+
+        let mut count = 0;
+        let mut last_attempt_at = Duration::ZERO;
+        let mut prev_sleep = base_delay;
+        let task = Retry::new("test")
+            .attempts(20)
+            .base_delay(base_delay)
+            .backoff(BackoffStrategy::Decorrelated { cap })
+            .run(async || {
+                count += 1;
+                let elapsed = start.elapsed();
+                if count > 1 {
+                    let slept = elapsed - last_attempt_at;
+                    assert!(slept >= base_delay);
+                    assert!(slept <= prev_sleep * 3);
+                    assert!(slept <= cap);
+                    prev_sleep = slept;
+                }
+                last_attempt_at = elapsed;
+                Err::<(), ()>(())
+            })
+            .await;
+        assert_eq!(count, 20);
+        assert!(task.is_err());
+    }
+
+    #[tokio::test]
+    async fn run_if_skips_non_retryable_errors() {
+        let mut count = 0;
+        let result = Retry::new("test")
+            .attempts(5)
+            .run_if(
+                async || {
+                    count += 1;
+                    Err::<(), _>("permanent error")
+                },
+                |err: &&str| *err != "permanent error",
+            )
+            .await;
+        assert_eq!(count, 1);
+        assert_eq!(result, Err(RetryError::Inner("permanent error")));
+    }
+
     #[tokio::test(start_paused = true)]
     async fn delayed_retry_with_jitter() {
         let start = Instant::now();
 
         let mut count = 0;
-        // Earliest possible retry is 0s, 50ms, 525ms, 5.525s
+        // Earliest possible retry is 0s, 75ms, 825ms, 8.325s
         let task = Retry::new("test_jitter")
             .attempts(4)
             .base_delay(Duration::from_millis(100))
@@ -194,4 +522,169 @@ mod tests {
         assert_eq!(count, 3);
         assert!(result.is_ok());
     }
+
+    #[tokio::test(start_paused = true)]
+    async fn attempt_timeout_retries_a_hanging_call() {
+        let mut count = 0;
+        let result = Retry::new("test")
+            .attempts(3)
+            .attempt_timeout(Duration::from_millis(100))
+            .run(async || {
+                count += 1;
+                if count < 3 {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                }
+                Ok::<_, std::io::Error>(())
+            })
+            .await;
+        assert_eq!(count, 3);
+        assert!(result.is_ok());
+    }
+
+    #[tokio::test]
+    async fn attempt_timeout_returns_timeout_error_when_exhausted() {
+        let result = Retry::new("test")
+            .attempts(2)
+            .attempt_timeout(Duration::from_millis(1))
+            .run(async || {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                Ok::<_, std::io::Error>(())
+            })
+            .await;
+        assert!(matches!(result, Err(RetryError::Timeout)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_with_invokes_on_retry_for_a_timed_out_attempt() {
+        let mut count = 0;
+        let mut notifications = Vec::new();
+        let result = Retry::new("test")
+            .attempts(2)
+            .attempt_timeout(Duration::from_millis(100))
+            .run_with(
+                async || {
+                    count += 1;
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    Ok::<_, std::io::Error>(())
+                },
+                |_err| true,
+                |attempt, err: Option<&std::io::Error>, delay| {
+                    notifications.push((attempt, err.is_some(), delay))
+                },
+            )
+            .await;
+        assert_eq!(count, 2);
+        assert!(matches!(result, Err(RetryError::Timeout)));
+        assert_eq!(notifications, vec![(0, false, Duration::ZERO)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn total_timeout_aborts_with_last_error() {
+        let mut count = 0;
+        // Attempts fall at t=0s, 1s, 2s; the sleep before a 4th attempt
+        // would reach the 3s deadline, so the loop aborts early and
+        // returns the 3rd attempt's error.
+        let result = Retry::new("test")
+            .attempts(100)
+            .base_delay(Duration::from_secs(1))
+            .total_timeout(Duration::from_secs(3))
+            .run(async || {
+                count += 1;
+                Err::<(), _>(count)
+            })
+            .await;
+        assert_eq!(count, 3);
+        assert_eq!(result, Err(RetryError::Inner(3)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn total_timeout_does_not_notify_for_the_aborted_attempt() {
+        let mut count = 0;
+        let mut notifications = Vec::new();
+        let result = Retry::new("test")
+            .attempts(100)
+            .base_delay(Duration::from_secs(1))
+            .total_timeout(Duration::from_secs(3))
+            .run_with(
+                async || {
+                    count += 1;
+                    Err::<(), _>(count)
+                },
+                |_err| true,
+                |attempt, err: Option<&i32>, delay| {
+                    notifications.push((attempt, err.copied(), delay))
+                },
+            )
+            .await;
+        assert_eq!(count, 3);
+        assert_eq!(result, Err(RetryError::Inner(3)));
+        assert_eq!(
+            notifications,
+            vec![
+                (0, Some(1), Duration::from_secs(1)),
+                (1, Some(2), Duration::from_secs(1))
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn run_with_invokes_on_retry_before_each_sleep() {
+        let mut count = 0;
+        let mut notifications = Vec::new();
+        let result = Retry::new("test")
+            .attempts(3)
+            .run_with(
+                async || {
+                    count += 1;
+                    Err::<(), _>(count)
+                },
+                |_err| true,
+                |attempt, err: Option<&i32>, delay| {
+                    notifications.push((attempt, err.copied(), delay))
+                },
+            )
+            .await;
+        assert_eq!(count, 3);
+        assert_eq!(result, Err(RetryError::Inner(3)));
+        assert_eq!(
+            notifications,
+            vec![(0, Some(1), Duration::ZERO), (1, Some(2), Duration::ZERO)]
+        );
+    }
+
+    #[tokio::test]
+    async fn run_detailed_reports_attempts_and_errors() {
+        let mut count = 0;
+        let outcome = Retry::new("test")
+            .attempts(3)
+            .run_detailed(async || {
+                count += 1;
+                if count < 3 {
+                    Err(count)
+                } else {
+                    Ok(())
+                }
+            })
+            .await;
+        assert_eq!(outcome.attempts, 3);
+        assert_eq!(outcome.errors, vec![1, 2]);
+        assert_eq!(outcome.result, Ok(()));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn run_detailed_excludes_final_error_on_total_timeout_abort() {
+        let mut count = 0;
+        let outcome = Retry::new("test")
+            .attempts(100)
+            .base_delay(Duration::from_secs(1))
+            .total_timeout(Duration::from_secs(3))
+            .run_detailed(async || {
+                count += 1;
+                Err::<(), _>(count)
+            })
+            .await;
+        assert_eq!(outcome.attempts, 3);
+        assert_eq!(outcome.errors, vec![1, 2]);
+        assert_eq!(outcome.result, Err(RetryError::Inner(3)));
+    }
 }